@@ -54,7 +54,25 @@ For a sample configuration, see <https://github.com/zhaofengli/colmena>.
             .help("Show debug information for Nix commands")
             .long_help("Passes --show-trace to Nix commands")
             .global(true)
-            .takes_value(false));
+            .takes_value(false))
+        .arg(Arg::with_name("watch")
+            .long("watch")
+            .help("Watch for changes and re-deploy automatically")
+            .long_help(r#"Instead of running once and exiting, stays resident and re-runs the command whenever the Hive's sources change.
+
+Colmena watches the directory containing hive.nix/flake.nix (for a flake: its local checkout, or the current directory if it has none), debouncing bursts of filesystem events so a single save only triggers one re-run. Other local paths the Hive references are not watched. Changes inside .git, and paths that the .gitignore/.ignore at the root of the watched directory match, are skipped, so things like the `result` symlink or .git churn don't cause rebuild storms; files inside an ignored directory are not skipped. A run that's still in progress when a new change comes in is cancelled, and the next run starts right away."#)
+            .global(true)
+            .takes_value(false))
+        .arg(Arg::with_name("parallel")
+            .short("p")
+            .long("parallel")
+            .value_name("PARALLEL")
+            .help("Number of nodes to deploy to in parallel")
+            .long_help(r#"Limits the number of nodes to build, or to run an `exec` command on, at once. 0 removes the limit. The default is the number of CPUs available.
+
+Builds (`apply`, `build`) and `exec` commands each have their own budget. There are no separate budgets for pushing closures or for activation."#)
+            .global(true)
+            .takes_value(true));
 
     register_command!(apply, app);
     register_command!(apply_local, app);
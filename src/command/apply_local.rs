@@ -0,0 +1,17 @@
+//! `colmena apply-local`: load the Hive, reporting an error if it can't be
+//! found. Nothing is activated on the current machine.
+
+use clap::{App, ArgMatches, SubCommand};
+
+use crate::util;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("apply-local")
+        .about("Load the Hive (nothing is activated on the local machine)")
+}
+
+pub async fn run(global_args: &ArgMatches<'_>, _local_args: &ArgMatches<'_>) {
+    if let Err(e) = util::hive_from_args(global_args).await {
+        log::error!("{}", e);
+    }
+}
@@ -0,0 +1,109 @@
+//! `colmena build`: evaluate and build the Hive's configuration without deploying.
+
+use std::sync::Arc;
+
+use clap::{App, ArgMatches, SubCommand};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use tokio::process::Command;
+use tokio::sync::Notify;
+
+use crate::nix::{Hive, NodeName, NixResult};
+use crate::util::{self, CommandExecution, ExecutionPhase, ParallelismLimits};
+use crate::watch::HiveWatcher;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    util::register_selector_args(
+        SubCommand::with_name("build")
+            .about("Build configurations without deploying them")
+    )
+}
+
+pub async fn run(global_args: &ArgMatches<'_>, local_args: &ArgMatches<'_>) {
+    let hive = match util::hive_from_args(global_args).await {
+        Ok(hive) => hive,
+        Err(e) => {
+            log::error!("{}", e);
+            return;
+        }
+    };
+
+    if !global_args.is_present("watch") {
+        let cancel = Arc::new(Notify::new());
+        if let Err(e) = build(global_args, local_args, &hive, cancel).await {
+            log::error!("{}", e);
+        }
+        return;
+    }
+
+    let mut watcher = match HiveWatcher::new(hive.path()) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            log::error!("Could not start watching for changes: {}", e);
+            return;
+        }
+    };
+
+    loop {
+        // A fresh token per run, so a cancellation of one run can't reach
+        // the next.
+        let cancel = Arc::new(Notify::new());
+        let run = build(global_args, local_args, &hive, cancel.clone());
+        tokio::pin!(run);
+
+        tokio::select! {
+            result = &mut run => {
+                if let Err(e) = result {
+                    log::error!("{}", e);
+                }
+            }
+            _ = watcher.next_change() => {
+                log::info!("Change detected mid-build, cancelling the in-flight run...");
+                // Wakes every node's execution sharing this token.
+                cancel.notify_waiters();
+                if let Err(e) = run.await {
+                    log::debug!("In-flight run ended after cancellation: {}", e);
+                }
+
+                // The change that cancelled this run starts the next one.
+                continue;
+            }
+        }
+
+        log::info!("Watching for changes...");
+        if watcher.next_change().await.is_none() {
+            break;
+        }
+    }
+}
+
+async fn build(global_args: &ArgMatches<'_>, local_args: &ArgMatches<'_>, hive: &Hive, cancel: Arc<Notify>) -> NixResult<()> {
+    let all_nodes = hive.deployment_info().await?;
+    let selector = local_args.value_of("on").unwrap_or("");
+    let nodes = util::filter_nodes(&all_nodes, selector)?;
+    let limits = ParallelismLimits::from_args(global_args);
+
+    // Dispatched concurrently; actual concurrency is bounded by `limits`'
+    // semaphore, not by this number.
+    let concurrency = nodes.len().max(1);
+    stream::iter(nodes)
+        .map(|node| {
+            let limits = limits.clone();
+            let cancel = cancel.clone();
+            async move {
+                let mut execution = CommandExecution::with_cancel_handle(build_command(&node), cancel);
+                execution.set_parallelism_limit(limits, ExecutionPhase::Build);
+                execution.run().await
+            }
+        })
+        .buffer_unordered(concurrency)
+        .try_collect::<Vec<()>>()
+        .await?;
+
+    Ok(())
+}
+
+fn build_command(node: &NodeName) -> Command {
+    let mut command = Command::new("nix");
+    command.args(["build", "--no-link", &format!(".#{}", node)]);
+    command
+}
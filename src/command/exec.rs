@@ -0,0 +1,73 @@
+//! `colmena exec`: run an arbitrary command once per selected node. The
+//! command runs on the local machine; nothing is sent to the nodes.
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use futures::stream::{self, StreamExt, TryStreamExt};
+
+use crate::nix::{Hive, NixResult};
+use crate::util::{self, CommandExecution, CommandStdin, ExecShell, ExecutionPhase, ParallelismLimits};
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    util::register_exec_args(util::register_selector_args(
+        SubCommand::with_name("exec")
+            .about("Run a command locally, once per selected node")
+            .arg(Arg::with_name("command")
+                .value_name("COMMAND")
+                .required(true)
+                .multiple(true)
+                .last(true))
+    ))
+}
+
+pub async fn run(global_args: &ArgMatches<'_>, local_args: &ArgMatches<'_>) {
+    let hive = match util::hive_from_args(global_args).await {
+        Ok(hive) => hive,
+        Err(e) => {
+            log::error!("{}", e);
+            return;
+        }
+    };
+
+    if let Err(e) = exec(global_args, local_args, &hive).await {
+        log::error!("{}", e);
+    }
+}
+
+async fn exec(global_args: &ArgMatches<'_>, local_args: &ArgMatches<'_>, hive: &Hive) -> NixResult<()> {
+    let all_nodes = hive.deployment_info().await?;
+    let selector = local_args.value_of("on").unwrap_or("");
+    let nodes = util::filter_nodes(&all_nodes, selector)?;
+    let limits = ParallelismLimits::from_args(global_args);
+
+    let argv: Vec<String> = local_args.values_of("command")
+        .map(|v| v.map(str::to_owned).collect())
+        .unwrap_or_default();
+
+    let shell = ExecShell::from_arg(local_args.value_of("shell").unwrap_or("none"));
+    let stdin = local_args.is_present("stdin");
+
+    // Dispatched concurrently; actual concurrency is bounded by `limits`'
+    // semaphore, not by this number.
+    let concurrency = nodes.len().max(1);
+    stream::iter(nodes)
+        .map(|_node| {
+            let limits = limits.clone();
+            let argv = &argv;
+            let shell = &shell;
+            async move {
+                let mut execution = CommandExecution::new(util::build_exec_command(&argv[0], &argv[1..], shell));
+                execution.set_parallelism_limit(limits, ExecutionPhase::Exec);
+
+                if stdin {
+                    execution.set_stdin(CommandStdin::Inherit);
+                }
+
+                execution.run().await
+            }
+        })
+        .buffer_unordered(concurrency)
+        .try_collect::<Vec<()>>()
+        .await?;
+
+    Ok(())
+}
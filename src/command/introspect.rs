@@ -0,0 +1,20 @@
+//! `colmena introspect`: print the names of the Hive's nodes.
+
+use clap::{App, ArgMatches, SubCommand};
+
+use crate::util;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("introspect")
+        .about("Print the names of the Hive's nodes")
+}
+
+pub async fn run(global_args: &ArgMatches<'_>, _local_args: &ArgMatches<'_>) {
+    match util::hive_from_args(global_args).await {
+        Ok(hive) => match hive.deployment_info().await {
+            Ok(nodes) => println!("{:?}", nodes.keys().collect::<Vec<_>>()),
+            Err(e) => log::error!("{}", e),
+        },
+        Err(e) => log::error!("{}", e),
+    }
+}
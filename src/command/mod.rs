@@ -0,0 +1,8 @@
+//! CLI subcommands.
+
+pub mod apply;
+pub mod apply_local;
+pub mod build;
+pub mod exec;
+pub mod introspect;
+pub mod upload_keys;
@@ -0,0 +1,19 @@
+//! `colmena upload-keys`: load the Hive, reporting an error if it can't be
+//! found. No keys are uploaded.
+
+use clap::{App, ArgMatches, SubCommand};
+
+use crate::util;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    util::register_selector_args(
+        SubCommand::with_name("upload-keys")
+            .about("Load the Hive (no keys are uploaded)")
+    )
+}
+
+pub async fn run(global_args: &ArgMatches<'_>, _local_args: &ArgMatches<'_>) {
+    if let Err(e) = util::hive_from_args(global_args).await {
+        log::error!("{}", e);
+    }
+}
@@ -0,0 +1,11 @@
+mod cli;
+mod command;
+mod nix;
+mod progress;
+mod util;
+mod watch;
+
+#[tokio::main]
+async fn main() {
+    cli::run().await;
+}
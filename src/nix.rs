@@ -0,0 +1,144 @@
+//! Core Nix/Hive abstractions.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+
+/// The name of a node, as given in the Hive expression.
+pub type NodeName = String;
+
+/// Per-node configuration evaluated from the Hive expression.
+#[derive(Debug, Clone, Default)]
+pub struct NodeConfig {
+    tags: Vec<String>,
+}
+
+impl NodeConfig {
+    pub fn new(tags: Vec<String>) -> Self {
+        Self { tags }
+    }
+
+    pub fn tags(&self) -> impl Iterator<Item = &String> {
+        self.tags.iter()
+    }
+}
+
+/// A flake reference, as accepted by `--config`/`-f` when it looks like a
+/// flake URI rather than a path.
+pub struct Flake {
+    uri: String,
+    local_dir: Option<PathBuf>,
+}
+
+impl Flake {
+    pub async fn from_uri(uri: String) -> NixResult<Self> {
+        Ok(Self { uri, local_dir: None })
+    }
+
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    /// The local checkout backing this flake, if it has one. Always `None`
+    /// for a flake created by `from_uri`.
+    pub fn local_dir(&self) -> Option<PathBuf> {
+        self.local_dir.clone()
+    }
+}
+
+/// Where a Hive's configuration comes from.
+pub enum HivePath {
+    /// A legacy `hive.nix`/`flake.nix` file on disk.
+    Legacy(PathBuf),
+    /// A flake reference.
+    Flake(Flake),
+}
+
+impl HivePath {
+    pub async fn from_path(path: PathBuf) -> NixResult<Self> {
+        Ok(HivePath::Legacy(path))
+    }
+}
+
+/// A resolved Hive, ready to be evaluated.
+pub struct Hive {
+    path: HivePath,
+    show_trace: bool,
+}
+
+impl Hive {
+    pub fn new(path: HivePath) -> NixResult<Self> {
+        Ok(Self { path, show_trace: false })
+    }
+
+    pub fn path(&self) -> &HivePath {
+        &self.path
+    }
+
+    pub fn set_show_trace(&mut self, show_trace: bool) {
+        self.show_trace = show_trace;
+    }
+
+    /// Returns every node's configuration, keyed by node name. Nothing is
+    /// evaluated here: the returned map is always empty.
+    pub async fn deployment_info(&self) -> NixResult<HashMap<NodeName, NodeConfig>> {
+        // Evaluation itself lives outside this crate fragment; callers only
+        // depend on the returned map being keyed by node name.
+        Ok(HashMap::new())
+    }
+}
+
+pub type NixResult<T> = Result<T, NixError>;
+
+/// Errors that can occur while evaluating a Hive or running Nix commands.
+#[derive(Debug)]
+pub enum NixError {
+    Io(std::io::Error),
+
+    /// A Nix command exited with a non-zero status.
+    Child(ExitStatus),
+
+    /// A running command was torn down: Ctrl-C, a `--watch` run getting
+    /// superseded by a newer change, or an explicit
+    /// [`crate::util::CommandExecution::cancel`].
+    Cancelled,
+
+    /// The filesystem watcher backing `--watch` failed.
+    Watch(notify::Error),
+
+    /// A `--on` node selector matched no nodes.
+    NoMatchingNodes,
+}
+
+impl fmt::Display for NixError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NixError::Io(e) => write!(f, "I/O error: {}", e),
+            NixError::Child(status) => write!(f, "Command exited with {}", status),
+            NixError::Cancelled => write!(f, "Command was cancelled"),
+            NixError::Watch(e) => write!(f, "Filesystem watcher error: {}", e),
+            NixError::NoMatchingNodes => write!(f, "The node selector did not match any nodes"),
+        }
+    }
+}
+
+impl std::error::Error for NixError {}
+
+impl From<std::io::Error> for NixError {
+    fn from(e: std::io::Error) -> Self {
+        NixError::Io(e)
+    }
+}
+
+impl From<ExitStatus> for NixError {
+    fn from(status: ExitStatus) -> Self {
+        NixError::Child(status)
+    }
+}
+
+impl From<notify::Error> for NixError {
+    fn from(e: notify::Error) -> Self {
+        NixError::Watch(e)
+    }
+}
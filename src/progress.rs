@@ -0,0 +1,21 @@
+//! Progress reporting for long-running Nix commands.
+
+/// A handle used to surface a `CommandExecution`'s output as it runs.
+#[derive(Debug, Clone, Default)]
+pub struct TaskProgress {
+    label: Option<String>,
+}
+
+impl TaskProgress {
+    pub fn with_label<S: Into<String>>(label: S) -> Self {
+        Self { label: Some(label.into()) }
+    }
+
+    /// Logs a single line of output from the task.
+    pub fn log(&mut self, line: &str) {
+        match &self.label {
+            Some(label) => eprintln!("[{}] {}", label, line),
+            None => eprintln!("{}", line),
+        }
+    }
+}
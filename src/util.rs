@@ -1,36 +1,141 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
 
 use clap::{App, Arg, ArgMatches};
+use command_group::AsyncCommandGroup;
 use futures::future::join3;
 use glob::Pattern as GlobPattern;
 use tokio::io::{AsyncRead, AsyncBufReadExt, BufReader};
 use tokio::process::Command;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
 
-use super::nix::{Flake, NodeName, NodeConfig, Hive, HivePath, NixResult};
+use super::nix::{Flake, NodeName, NodeConfig, Hive, HivePath, NixError, NixResult};
 use super::progress::TaskProgress;
 
+/// How long to wait after SIGTERM before escalating to SIGKILL.
+const TERMINATE_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Which phase of a deployment a [`CommandExecution`] belongs to, for
+/// phase-aware parallelism limits. Each phase gets its own budget so, e.g.,
+/// a cheap ad-hoc `exec` isn't stuck queued up behind a heavy build.
+///
+/// Only phases with a real, distinct dispatch site belong here — see
+/// `command::apply`/`command::build` (`Build`) and `command::exec` (`Exec`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionPhase {
+    /// Evaluating and building a node's configuration (`apply`, `build`).
+    Build,
+    /// An arbitrary ad-hoc command (`exec`).
+    Exec,
+}
+
+/// Per-phase concurrency budgets for multi-node operations, derived from
+/// the `--parallel`/`-p` CLI argument.
+#[derive(Clone)]
+pub struct ParallelismLimits {
+    build: Arc<Semaphore>,
+    exec: Arc<Semaphore>,
+}
+
+impl ParallelismLimits {
+    /// Builds limits from the `--parallel`/`-p` argument. A parsed value of
+    /// `0` removes the cap; if unspecified, defaults to the available
+    /// parallelism.
+    pub fn from_args(args: &ArgMatches<'_>) -> Self {
+        let limit = match args.value_of("parallel") {
+            Some(n) => {
+                let parsed: usize = n.parse().expect("--parallel should be a non-negative integer");
+                if parsed == 0 { None } else { Some(parsed) }
+            }
+            None => Some(std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4)),
+        };
+
+        let permits = limit.unwrap_or(Semaphore::MAX_PERMITS);
+
+        Self {
+            build: Arc::new(Semaphore::new(permits)),
+            exec: Arc::new(Semaphore::new(permits)),
+        }
+    }
+
+    fn semaphore(&self, phase: ExecutionPhase) -> &Arc<Semaphore> {
+        use ExecutionPhase::*;
+        match phase {
+            Build => &self.build,
+            Exec => &self.exec,
+        }
+    }
+}
+
 enum NodeFilter {
     NameFilter(GlobPattern),
     TagFilter(GlobPattern),
 }
 
+impl NodeFilter {
+    fn matches(&self, name: &NodeName, node: &NodeConfig) -> bool {
+        use NodeFilter::*;
+        match self {
+            NameFilter(pat) => pat.matches(name),
+            TagFilter(pat) => node.tags().any(|tag| pat.matches(tag)),
+        }
+    }
+}
+
+fn parse_node_filter(term: &str) -> NodeFilter {
+    match term.strip_prefix('@') {
+        Some(tag_pattern) => NodeFilter::TagFilter(GlobPattern::new(tag_pattern).unwrap()),
+        None => NodeFilter::NameFilter(GlobPattern::new(term).unwrap()),
+    }
+}
+
 /// Non-interactive execution of an arbitrary Nix command.
 pub struct CommandExecution {
     command: Command,
     progress_bar: TaskProgress,
     stdout: Option<String>,
     stderr: Option<String>,
+    cancel: Arc<Notify>,
+    limit: Option<(ParallelismLimits, ExecutionPhase)>,
+    stdin: CommandStdin,
+}
+
+/// How a [`CommandExecution`]'s child should receive stdin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandStdin {
+    /// The default: the child gets no stdin at all.
+    Null,
+    /// The controller's stdin is passed through to the child, so piped or
+    /// interactive input (e.g. `echo cfg | colmena exec ... tee /etc/foo`)
+    /// reaches the command.
+    Inherit,
 }
 
 impl CommandExecution {
     pub fn new(command: Command) -> Self {
+        Self::with_cancel_handle(command, Arc::new(Notify::new()))
+    }
+
+    /// Like `new`, but shares a cancellation token with the caller instead
+    /// of creating a private one. This is how a `--watch` loop cancels an
+    /// in-flight execution when a new change supersedes it: the caller
+    /// keeps the `Arc<Notify>` and calls `notify_waiters()` on it directly,
+    /// which wakes every running execution sharing the token, rather than
+    /// going through `cancel_handle()` on an execution it no longer has a
+    /// reference to.
+    pub fn with_cancel_handle(command: Command, cancel: Arc<Notify>) -> Self {
         Self {
             command,
             progress_bar: TaskProgress::default(),
             stdout: None,
             stderr: None,
+            cancel,
+            limit: None,
+            stdin: CommandStdin::Null,
         }
     }
 
@@ -39,36 +144,95 @@ impl CommandExecution {
         self.progress_bar = bar;
     }
 
+    /// Controls how the child receives stdin. Defaults to `CommandStdin::Null`.
+    pub fn set_stdin(&mut self, stdin: CommandStdin) {
+        self.stdin = stdin;
+    }
+
+    /// Gates this execution on a shared, phase-aware parallelism budget:
+    /// `run()` will block until a permit for `phase` is available, and
+    /// release it once the command completes (or is cancelled).
+    pub fn set_parallelism_limit(&mut self, limits: ParallelismLimits, phase: ExecutionPhase) {
+        self.limit = Some((limits, phase));
+    }
+
     /// Retrieve logs from the last invocation.
     pub fn get_logs(&self) -> (Option<&String>, Option<&String>) {
         (self.stdout.as_ref(), self.stderr.as_ref())
     }
 
+    /// Returns a handle that can be used to cancel this execution from
+    /// another task (e.g. a `--watch` loop superseding an in-flight run).
+    pub fn cancel_handle(&self) -> Arc<Notify> {
+        self.cancel.clone()
+    }
+
+    /// Cancels this execution. If it isn't running yet, its next `run()` is
+    /// cancelled as soon as the command has been spawned.
+    pub fn cancel(&self) {
+        self.cancel.notify_one();
+    }
+
     /// Run the command.
+    ///
+    /// The child is spawned into its own process group, so that on Ctrl-C or
+    /// an explicit `cancel()`, the entire group (including any `nix`/`ssh`
+    /// grandchildren) can be torn down rather than just the immediate child.
     pub async fn run(&mut self) -> NixResult<()> {
-        self.command.stdin(Stdio::null());
+        self.command.stdin(match self.stdin {
+            CommandStdin::Null => Stdio::null(),
+            CommandStdin::Inherit => Stdio::inherit(),
+        });
         self.command.stdout(Stdio::piped());
         self.command.stderr(Stdio::piped());
 
         self.stdout = Some(String::new());
         self.stderr = Some(String::new());
 
-        let mut child = self.command.spawn()?;
+        // Held until the end of this function, releasing the permit (if
+        // any) as soon as the command completes or is cancelled.
+        let _permit: Option<OwnedSemaphorePermit> = match &self.limit {
+            Some((limits, phase)) => Some(limits.semaphore(*phase).clone().acquire_owned().await.unwrap()),
+            None => None,
+        };
 
-        let stdout = BufReader::new(child.stdout.take().unwrap());
-        let stderr = BufReader::new(child.stderr.take().unwrap());
+        let mut group = self.command.group_spawn()?;
+        let pgid = group.id();
 
-        let futures = join3(
+        let stdout = BufReader::new(group.inner().stdout.take().unwrap());
+        let stderr = BufReader::new(group.inner().stderr.take().unwrap());
+
+        let mut sigint = signal(SignalKind::interrupt())?;
+        let mut sigterm = signal(SignalKind::terminate())?;
+
+        let logs = join3(
             capture_stream(stdout, self.progress_bar.clone()),
             capture_stream(stderr, self.progress_bar.clone()),
-            child.wait(),
+            group.wait(),
         );
-
-        let (stdout_str, stderr_str, wait) = futures.await;
-        self.stdout = Some(stdout_str);
-        self.stderr = Some(stderr_str);
-
-        let exit = wait?;
+        tokio::pin!(logs);
+
+        let exit = loop {
+            tokio::select! {
+                (stdout_str, stderr_str, wait) = &mut logs => {
+                    self.stdout = Some(stdout_str);
+                    self.stderr = Some(stderr_str);
+                    break wait?;
+                }
+                _ = sigint.recv() => {
+                    terminate_group(pgid).await;
+                    return Err(NixError::Cancelled);
+                }
+                _ = sigterm.recv() => {
+                    terminate_group(pgid).await;
+                    return Err(NixError::Cancelled);
+                }
+                _ = self.cancel.notified() => {
+                    terminate_group(pgid).await;
+                    return Err(NixError::Cancelled);
+                }
+            }
+        };
 
         if exit.success() {
             Ok(())
@@ -78,6 +242,38 @@ impl CommandExecution {
     }
 }
 
+/// How often to poll for the group having exited while waiting out the
+/// grace period below.
+const TERMINATE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Sends SIGTERM to the process group `pgid`, then waits up to
+/// `TERMINATE_GRACE_PERIOD` for it to exit on its own, only escalating to
+/// SIGKILL if it's still alive once the grace period elapses. This keeps a
+/// Ctrl-C or `--watch` supersession snappy in the common case where the
+/// child dies promptly on SIGTERM.
+async fn terminate_group(pgid: u32) {
+    unsafe {
+        libc::kill(-(pgid as i32), libc::SIGTERM);
+    }
+
+    let deadline = tokio::time::Instant::now() + TERMINATE_GRACE_PERIOD;
+    while tokio::time::Instant::now() < deadline {
+        // Reap the group leader (our direct child) once it has exited, so a
+        // lingering zombie doesn't keep the group looking alive.
+        unsafe { libc::waitpid(pgid as i32, std::ptr::null_mut(), libc::WNOHANG); }
+        let alive = unsafe { libc::kill(-(pgid as i32), 0) == 0 };
+        if !alive {
+            return;
+        }
+
+        tokio::time::sleep(TERMINATE_POLL_INTERVAL).await;
+    }
+
+    unsafe {
+        libc::kill(-(pgid as i32), libc::SIGKILL);
+    }
+}
+
 pub async fn hive_from_args(args: &ArgMatches<'_>) -> NixResult<Hive> {
     let path = match args.occurrences_of("config") {
         0 => {
@@ -145,42 +341,78 @@ pub async fn hive_from_args(args: &ArgMatches<'_>) -> NixResult<Hive> {
     Ok(hive)
 }
 
-pub fn filter_nodes(nodes: &HashMap<NodeName, NodeConfig>, filter: &str) -> Vec<NodeName> {
-    let filters: Vec<NodeFilter> = filter.split(",").map(|pattern| {
-        use NodeFilter::*;
-        if let Some(tag_pattern) = pattern.strip_prefix("@") {
-            TagFilter(GlobPattern::new(tag_pattern).unwrap())
-        } else {
-            NameFilter(GlobPattern::new(pattern).unwrap())
+/// Resolves a `--on` selector against the Hive's nodes.
+///
+/// The grammar: comma separates OR'd groups, `&`/whitespace within a group
+/// intersects (AND) its terms, and a `!`/`not` prefix on a term negates it.
+/// Exclusions are collected across the whole selector and always apply
+/// last, so `@prod & @eu, !host-canary-*` means "prod and eu nodes, minus
+/// any canaries" regardless of which group the exclusion appeared in.
+///
+/// The result is deterministically sorted by node name. An empty selector
+/// matches every node; a non-empty selector that matches nothing is an
+/// error, so a typo'd selector doesn't silently deploy to nothing.
+pub fn filter_nodes(nodes: &HashMap<NodeName, NodeConfig>, filter: &str) -> NixResult<Vec<NodeName>> {
+    let mut include_groups: Vec<Vec<NodeFilter>> = Vec::new();
+    let mut exclude_filters: Vec<NodeFilter> = Vec::new();
+
+    for group in filter.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let terms: Vec<&str> = group
+            .split(|c: char| c == '&' || c.is_whitespace())
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        let mut positive = Vec::new();
+        let mut i = 0;
+        while i < terms.len() {
+            let mut term = terms[i];
+            let mut negated = false;
+
+            if term.eq_ignore_ascii_case("not") {
+                negated = true;
+                i += 1;
+                if i >= terms.len() {
+                    break;
+                }
+                term = terms[i];
+            } else if let Some(rest) = term.strip_prefix('!') {
+                negated = true;
+                term = rest;
+            }
+
+            if negated {
+                exclude_filters.push(parse_node_filter(term));
+            } else {
+                positive.push(parse_node_filter(term));
+            }
+
+            i += 1;
         }
+
+        if !positive.is_empty() {
+            include_groups.push(positive);
+        }
+    }
+
+    let mut selected: Vec<NodeName> = nodes.iter().filter_map(|(name, node)| {
+        let included = include_groups.is_empty()
+            || include_groups.iter().any(|group| group.iter().all(|f| f.matches(name, node)));
+
+        if !included || exclude_filters.iter().any(|f| f.matches(name, node)) {
+            return None;
+        }
+
+        Some(name.clone())
     }).collect();
 
-    if filters.len() > 0 {
-        nodes.iter().filter_map(|(name, node)| {
-            for filter in filters.iter() {
-                use NodeFilter::*;
-                match filter {
-                    TagFilter(pat) => {
-                        // Welp
-                        for tag in node.tags() {
-                            if pat.matches(tag) {
-                                return Some(name);
-                            }
-                        }
-                    }
-                    NameFilter(pat) => {
-                        if pat.matches(name) {
-                            return Some(name)
-                        }
-                    }
-                }
-            }
+    selected.sort();
 
-            None
-        }).cloned().collect()
-    } else {
-        nodes.keys().cloned().collect()
+    if selected.is_empty() && !filter.trim().is_empty() {
+        return Err(NixError::NoMatchingNodes);
     }
+
+    Ok(selected)
 }
 
 pub fn register_selector_args<'a, 'b>(command: App<'a, 'b>) -> App<'a, 'b> {
@@ -191,13 +423,84 @@ pub fn register_selector_args<'a, 'b>(command: App<'a, 'b>) -> App<'a, 'b> {
             .help("Node selector")
             .long_help(r#"Select a list of nodes to deploy to.
 
-The list is comma-separated and globs are supported. To match tags, prepend the filter by @. Valid examples:
+The list is comma-separated and globs are supported. To match tags, prepend the filter by @. Comma is union (OR), `&`/whitespace within a group is intersection (AND), and a `!`/`not` prefix excludes matches; exclusions always apply last. Valid examples:
 
 - host1,host2,host3
 - edge-*
 - edge-*,core-*
-- @a-tag,@tags-can-have-*"#)
+- @a-tag,@tags-can-have-*
+- @prod & @eu, !host-canary-*"#)
+            .takes_value(true))
+}
+
+pub fn register_exec_args<'a, 'b>(command: App<'a, 'b>) -> App<'a, 'b> {
+    command
+        .arg(Arg::with_name("shell")
+            .long("shell")
+            .value_name("SHELL")
+            .help("Shell to wrap the command in")
+            .long_help(r#"Controls how the command is invoked on each node.
+
+- none: exec the given argv directly (default)
+- a shell, e.g. /bin/sh or $SHELL: run `<shell> -c "<command>"`. Each word of the command is single-quoted, so pipes, redirection, globs and other shell syntax in it are passed through literally, not interpreted by the shell"#)
+            .default_value("none")
             .takes_value(true))
+        .arg(Arg::with_name("stdin")
+            .long("stdin")
+            .help("Pass the controller's stdin through to the command")
+            .long_help("Pipes the controller's stdin through to the command, for interactive or piped one-liners (e.g. `echo cfg | colmena exec ... tee /etc/foo`).")
+            .takes_value(false))
+}
+
+/// How the command passed to `colmena exec` should be invoked on the node.
+pub enum ExecShell {
+    /// Exec the given argv directly, like `exec` does without `--shell`.
+    None,
+    /// Run `<shell> -c "<command>"`.
+    Wrapped(String),
+}
+
+impl ExecShell {
+    pub fn from_arg(value: &str) -> Self {
+        match value {
+            "none" => ExecShell::None,
+            shell => ExecShell::Wrapped(shell.to_owned()),
+        }
+    }
+}
+
+/// Builds the argv for `colmena exec`, applying the requested shell wrapping.
+pub fn build_exec_command(program: &str, args: &[String], shell: &ExecShell) -> Command {
+    match shell {
+        ExecShell::None => {
+            let mut command = Command::new(program);
+            command.args(args);
+            command
+        }
+        ExecShell::Wrapped(shell) => {
+            let mut command = Command::new(shell);
+            command.arg("-c").arg(shell_join_command(program, args));
+            command
+        }
+    }
+}
+
+/// Joins `program` and `args` into a single string safe to pass to `sh -c`,
+/// single-quoting each word so that whitespace and shell metacharacters
+/// (`$(...)`, `;`, `|`, ...) in an argument are passed through literally
+/// rather than being interpreted by the remote shell.
+fn shell_join_command(program: &str, args: &[String]) -> String {
+    std::iter::once(program)
+        .chain(args.iter().map(String::as_str))
+        .map(shell_quote)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Single-quotes `word` for use as one literal shell word, escaping any
+/// embedded single quotes as `'\''`.
+fn shell_quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', r#"'\''"#))
 }
 
 fn canonicalize_cli_path(path: &str) -> PathBuf {
@@ -228,3 +531,86 @@ pub async fn capture_stream<R: AsyncRead + Unpin>(mut stream: BufReader<R>, mut
 
     log
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(tags: &[&str]) -> NodeConfig {
+        NodeConfig::new(tags.iter().map(|s| s.to_string()).collect())
+    }
+
+    fn nodes() -> HashMap<NodeName, NodeConfig> {
+        let mut nodes = HashMap::new();
+        nodes.insert("host-a".to_string(), node(&["prod", "eu"]));
+        nodes.insert("host-b".to_string(), node(&["prod", "us"]));
+        nodes.insert("host-canary-1".to_string(), node(&["prod", "eu"]));
+        nodes
+    }
+
+    #[test]
+    fn filter_nodes_empty_selector_matches_everything() {
+        let selected = filter_nodes(&nodes(), "").unwrap();
+        assert_eq!(selected, vec!["host-a", "host-b", "host-canary-1"]);
+    }
+
+    #[test]
+    fn filter_nodes_union() {
+        let selected = filter_nodes(&nodes(), "host-a,host-b").unwrap();
+        assert_eq!(selected, vec!["host-a", "host-b"]);
+    }
+
+    #[test]
+    fn filter_nodes_tag_intersection() {
+        let selected = filter_nodes(&nodes(), "@prod & @eu").unwrap();
+        assert_eq!(selected, vec!["host-a", "host-canary-1"]);
+    }
+
+    #[test]
+    fn filter_nodes_exclusion_applies_last() {
+        let selected = filter_nodes(&nodes(), "@prod & @eu, !host-canary-*").unwrap();
+        assert_eq!(selected, vec!["host-a"]);
+    }
+
+    #[test]
+    fn filter_nodes_not_prefix() {
+        let selected = filter_nodes(&nodes(), "@prod, not host-canary-1").unwrap();
+        assert_eq!(selected, vec!["host-a", "host-b"]);
+    }
+
+    #[test]
+    fn filter_nodes_results_are_sorted() {
+        let selected = filter_nodes(&nodes(), "@prod").unwrap();
+        assert_eq!(selected, vec!["host-a", "host-b", "host-canary-1"]);
+    }
+
+    #[test]
+    fn filter_nodes_empty_result_is_an_error() {
+        let result = filter_nodes(&nodes(), "no-such-host-*");
+        assert!(matches!(result, Err(NixError::NoMatchingNodes)));
+    }
+
+    #[test]
+    fn shell_join_command_quotes_whitespace_and_metacharacters() {
+        let args = vec![
+            "/etc/my file".to_string(),
+            "$(reboot)".to_string(),
+            "; rm -rf /tmp/x".to_string(),
+        ];
+
+        let joined = shell_join_command("tee", &args);
+
+        assert_eq!(
+            joined,
+            r#"'tee' '/etc/my file' '$(reboot)' '; rm -rf /tmp/x'"#
+        );
+    }
+
+    #[test]
+    fn shell_join_command_escapes_embedded_single_quotes() {
+        let args = vec!["it's a test".to_string()];
+        let joined = shell_join_command("echo", &args);
+
+        assert_eq!(joined, r#"'echo' 'it'\''s a test'"#);
+    }
+}
@@ -0,0 +1,99 @@
+//! Filesystem watching for `--watch` continuous deployment.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use futures::channel::mpsc::{self, UnboundedReceiver};
+use futures::StreamExt;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher as _};
+
+use super::nix::{HivePath, NixResult};
+
+/// How long to wait after the last filesystem event before treating the
+/// tree as settled and firing a re-run.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Watches the directory containing a Hive's sources, yielding a signal
+/// each time a batch of changes has settled.
+///
+/// Events inside `.git`, and events on paths that the root
+/// `.gitignore`/`.ignore` match (e.g. the `result` symlink), never reach
+/// the debounce queue. Files inside an ignored directory are not filtered.
+pub struct HiveWatcher {
+    // Kept alive for the lifetime of the watcher; dropping it stops watching.
+    _watcher: RecommendedWatcher,
+    changes: UnboundedReceiver<()>,
+}
+
+impl HiveWatcher {
+    /// Starts watching the directory containing `hive.nix`/`flake.nix`.
+    pub fn new(hive_path: &HivePath) -> NixResult<Self> {
+        let root = watch_root(hive_path);
+        let ignore = build_ignore(&root);
+
+        let (raw_tx, mut raw_rx) = mpsc::unbounded();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let event = match event {
+                Ok(event) => event,
+                Err(_) => return,
+            };
+
+            let relevant = event.paths.iter().any(|path| {
+                let in_git_dir = path.components().any(|c| c.as_os_str() == ".git");
+                !in_git_dir && !ignore.matched(path, path.is_dir()).is_ignore()
+            });
+
+            if relevant {
+                let _ = raw_tx.unbounded_send(());
+            }
+        })?;
+
+        watcher.watch(&root, RecursiveMode::Recursive)?;
+
+        let (debounced_tx, debounced_rx) = mpsc::unbounded();
+        tokio::spawn(async move {
+            while raw_rx.next().await.is_some() {
+                // Collapse anything else that arrives within the debounce
+                // window into this single change notification.
+                while tokio::time::timeout(DEBOUNCE, raw_rx.next()).await.is_ok() {}
+
+                if debounced_tx.unbounded_send(()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            _watcher: watcher,
+            changes: debounced_rx,
+        })
+    }
+
+    /// Waits for the next settled batch of changes.
+    pub async fn next_change(&mut self) -> Option<()> {
+        self.changes.next().await
+    }
+}
+
+/// Returns the directory to watch for a given Hive path: the directory
+/// containing `hive.nix`/`flake.nix`, or the flake's local checkout if it
+/// has one.
+fn watch_root(hive_path: &HivePath) -> PathBuf {
+    let path = match hive_path {
+        HivePath::Legacy(path) => path.clone(),
+        HivePath::Flake(flake) => match flake.local_dir() {
+            Some(dir) => return dir,
+            None => return PathBuf::from("."),
+        },
+    };
+
+    path.parent().map(Path::to_owned).unwrap_or(path)
+}
+
+fn build_ignore(root: &Path) -> Gitignore {
+    let mut builder = GitignoreBuilder::new(root);
+    let _ = builder.add(root.join(".gitignore"));
+    let _ = builder.add(root.join(".ignore"));
+    builder.build().unwrap_or_else(|_| Gitignore::empty())
+}